@@ -0,0 +1,187 @@
+//! Backends that resolve a block to its transaction ids and fetch per-transaction fee data.
+//!
+//! [`EsploraBackend`] talks to an Esplora-compatible REST API (the original, default
+//! behaviour); [`ElectrumBackend`] talks to an Electrum server instead, for users who'd
+//! rather not depend on a single public REST provider.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bitcoin::{BlockHash, Txid};
+use electrum_client::{Client as ElectrumClient, ElectrumApi};
+use reqwest::{Client, Url};
+use rust_decimal::prelude::*;
+
+use crate::{ensure_found, get_block_transaction_ids, get_transaction, AppError, Transaction};
+
+/// A source of block transaction ids and transaction fee data.
+#[async_trait::async_trait]
+pub trait FeeBackend: Send + Sync {
+    /// All transaction ids in the block, coinbase first.
+    async fn get_block_txids(&self, block_hash: &str) -> Result<Vec<String>, AppError>;
+
+    /// Fee and weight of a single transaction.
+    async fn get_transaction(&self, txid: &str) -> Result<Transaction, AppError>;
+
+    /// The hash of the block at `height`.
+    async fn block_hash_at_height(&self, height: u64) -> Result<String, AppError>;
+}
+
+/// Fetches block and transaction data from an Esplora-compatible REST API.
+pub struct EsploraBackend {
+    client: Client,
+    url: Url,
+}
+
+impl EsploraBackend {
+    pub fn new(client: Client, url: Url) -> Self {
+        Self { client, url }
+    }
+}
+
+#[async_trait::async_trait]
+impl FeeBackend for EsploraBackend {
+    async fn get_block_txids(&self, block_hash: &str) -> Result<Vec<String>, AppError> {
+        get_block_transaction_ids(block_hash, &self.client, &self.url).await
+    }
+
+    async fn get_transaction(&self, txid: &str) -> Result<Transaction, AppError> {
+        get_transaction(txid, &self.client, &self.url).await
+    }
+
+    async fn block_hash_at_height(&self, height: u64) -> Result<String, AppError> {
+        let url = self.url.join("block-height/")?.join(&height.to_string())?;
+        let response = ensure_found(self.client.get(url).send().await?).await?;
+        Ok(response.text().await?.trim().to_string())
+    }
+}
+
+/// Fetches block and transaction data from an Electrum server (`ssl://`/`tcp://`).
+///
+/// Electrum's protocol is height-indexed rather than hash-indexed and has no `hash ->
+/// height` lookup, so resolving a block hash costs O(chain height / [`HEADER_BATCH_SIZE`])
+/// RPC round-trips: [`ElectrumBackend::resolve_height`] fetches headers in batches, walking
+/// backwards from the tip (recent blocks, the common case, are found in the first batch).
+pub struct ElectrumBackend {
+    client: Arc<ElectrumClient>,
+}
+
+/// Headers fetched per `blockchain.block.headers` call while resolving a hash to a height.
+const HEADER_BATCH_SIZE: u32 = 2_000;
+
+impl ElectrumBackend {
+    /// Connect to an Electrum server, e.g. `ssl://electrum.blockstream.info:50002`.
+    pub fn connect(url: &str) -> Result<Self, AppError> {
+        let client = ElectrumClient::new(url).map_err(|error| AppError::Backend(error.to_string()))?;
+        Ok(Self { client: Arc::new(client) })
+    }
+
+    /// Find the height of `block_hash` by fetching headers in batches from the chain tip
+    /// backwards to genesis.
+    fn resolve_height(client: &ElectrumClient, block_hash: BlockHash) -> Result<u32, AppError> {
+        let tip_height = client
+            .block_headers_subscribe()
+            .map_err(|error| AppError::Backend(error.to_string()))?
+            .height as u32;
+
+        let mut batch_end = tip_height;
+        loop {
+            let batch_start = batch_end.saturating_sub(HEADER_BATCH_SIZE - 1);
+            let count = (batch_end - batch_start + 1) as usize;
+            let batch = client
+                .block_headers(batch_start as usize, count)
+                .map_err(|error| AppError::Backend(error.to_string()))?;
+            for (offset, header) in batch.headers.iter().enumerate() {
+                if header.block_hash() == block_hash {
+                    return Ok(batch_start + offset as u32);
+                }
+            }
+            if batch_start == 0 {
+                return Err(AppError::NotFound(format!("block {block_hash}")));
+            }
+            batch_end = batch_start - 1;
+        }
+    }
+
+    /// Value, in satoshis, of output `vout` of a previously-broadcast transaction.
+    fn output_value(client: &ElectrumClient, txid: Txid, vout: u32) -> Result<u64, AppError> {
+        let tx = client
+            .transaction_get(&txid)
+            .map_err(|error| AppError::Backend(error.to_string()))?;
+        tx.output
+            .get(vout as usize)
+            .map(|output| output.value.to_sat())
+            .ok_or_else(|| AppError::Backend(format!("vout {vout} missing from {txid}")))
+    }
+}
+
+#[async_trait::async_trait]
+impl FeeBackend for ElectrumBackend {
+    async fn get_block_txids(&self, block_hash: &str) -> Result<Vec<String>, AppError> {
+        let block_hash = BlockHash::from_str(block_hash.trim_end_matches('/'))
+            .map_err(|error| AppError::Backend(error.to_string()))?;
+        let client = self.client.clone();
+        tokio::task::spawn_blocking(move || {
+            let height = Self::resolve_height(&client, block_hash)?;
+            let mut txids = Vec::new();
+            for tx_pos in 0.. {
+                match client.transaction_id_from_pos(height as usize, tx_pos, false) {
+                    Ok(txid) => txids.push(txid.to_string()),
+                    // The server reports "out of range" once `tx_pos` runs past the end of the
+                    // block; any other error (dropped connection, timeout, ...) is propagated
+                    // instead of being mistaken for the end of the block.
+                    Err(electrum_client::Error::Protocol(ref value)) if value.to_string().contains("out of range") => {
+                        break;
+                    }
+                    Err(error) => return Err(AppError::Backend(error.to_string())),
+                }
+            }
+            Ok(txids)
+        })
+        .await
+        .expect("Electrum task panicked.")
+    }
+
+    async fn get_transaction(&self, txid: &str) -> Result<Transaction, AppError> {
+        let txid = Txid::from_str(txid).map_err(|error| AppError::Backend(error.to_string()))?;
+        let client = self.client.clone();
+        tokio::task::spawn_blocking(move || {
+            let tx = client
+                .transaction_get(&txid)
+                .map_err(|error| AppError::Backend(error.to_string()))?;
+
+            let output_total: u64 = tx.output.iter().map(|output| output.value.to_sat()).sum();
+            let fee = if tx.is_coinbase() {
+                0
+            } else {
+                let input_total: u64 = tx
+                    .input
+                    .iter()
+                    .map(|input| Self::output_value(&client, input.previous_output.txid, input.previous_output.vout))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into_iter()
+                    .sum();
+                input_total.saturating_sub(output_total)
+            };
+
+            Ok(Transaction {
+                fee: Decimal::from(fee),
+                weight: u64::from(tx.weight().to_wu()),
+            })
+        })
+        .await
+        .expect("Electrum task panicked.")
+    }
+
+    async fn block_hash_at_height(&self, height: u64) -> Result<String, AppError> {
+        let client = self.client.clone();
+        tokio::task::spawn_blocking(move || {
+            let header = client
+                .block_header(height as usize)
+                .map_err(|error| AppError::Backend(error.to_string()))?;
+            Ok(header.block_hash().to_string())
+        })
+        .await
+        .expect("Electrum task panicked.")
+    }
+}
@@ -0,0 +1,377 @@
+//! Compute the sum (and fee-rate distribution) of the transaction fees of a Bitcoin block.
+//!
+//! The binary (`src/main.rs`) is a thin CLI wrapper around this crate; callers that want to
+//! embed the fee computation elsewhere can depend on this library directly, starting from
+//! [`block_fee_total`] or the lower-level [`backend::FeeBackend`] trait.
+#![warn(clippy::pedantic)]
+#[macro_use] extern crate log;
+
+pub mod backend;
+
+use std::time::Duration;
+
+use backoff::ExponentialBackoff;
+use reqwest::{Client, Url};
+use rust_decimal::prelude::*;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub use backend::FeeBackend;
+
+// Documentation: https://github.com/Blockstream/esplora/blob/master/API.md
+pub const MAINNET_ESPLORA_API: &str = "https://blockstream.info/api/";
+pub const TESTNET_ESPLORA_API: &str = "https://blockstream.info/testnet/api/";
+pub const SIGNET_ESPLORA_API: &str = "https://mempool.space/signet/api/";
+pub const REGTEST_ESPLORA_API: &str = "http://127.0.0.1:3000/api/";
+const MAINNET_MAGIC_NUMBER: &str = "00000000";
+
+/// Which Bitcoin network to query, following the same split used by `bitcoin::Network`.
+#[derive(Debug, Clone, Copy)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+impl Network {
+    /// Default Esplora base URL for this network, used unless overridden by the caller.
+    #[must_use]
+    pub fn default_esplora_url(self) -> &'static str {
+        match self {
+            Network::Mainnet => MAINNET_ESPLORA_API,
+            Network::Testnet => TESTNET_ESPLORA_API,
+            Network::Signet => SIGNET_ESPLORA_API,
+            Network::Regtest => REGTEST_ESPLORA_API,
+        }
+    }
+
+    /// Leading hex digits every valid block hash on this network starts with.
+    ///
+    /// Mainnet's proof-of-work difficulty guarantees a long run of zeros; the other
+    /// networks have much lower (or no enforced) difficulty, so only a single leading
+    /// zero nibble is required.
+    #[must_use]
+    pub fn hash_prefix(self) -> &'static str {
+        match self {
+            Network::Mainnet => MAINNET_MAGIC_NUMBER,
+            Network::Testnet | Network::Signet | Network::Regtest => "0",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Transaction {
+    pub fee: Decimal,
+    pub weight: u64,
+}
+
+impl Transaction {
+    /// Fee rate in sat/vByte, using `vsize = ceil(weight / 4)`.
+    #[must_use]
+    pub fn fee_rate(&self) -> Decimal {
+        let vsize = Decimal::from(self.weight.div_ceil(4));
+        if vsize.is_zero() {
+            return dec!(0);
+        }
+        self.fee / vsize
+    }
+}
+
+/// Fee-rate distribution (sat/vByte) across a block's non-coinbase transactions.
+#[derive(Debug, Serialize)]
+pub struct FeeRateStats {
+    pub min: Decimal,
+    pub max: Decimal,
+    pub mean: Decimal,
+    pub median: Decimal,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub percentiles: Vec<(u8, Decimal)>,
+}
+
+impl FeeRateStats {
+    /// Build the distribution, sorting `fee_rates` in place. `percentiles` are each in `0..=100`.
+    #[must_use]
+    pub fn new(fee_rates: &mut [Decimal], percentiles: &[u8]) -> Option<Self> {
+        if fee_rates.is_empty() {
+            return None;
+        }
+        fee_rates.sort_unstable();
+        let len = fee_rates.len();
+        let sum: Decimal = fee_rates.iter().sum();
+        let median = if len % 2 == 0 {
+            (fee_rates[len / 2 - 1] + fee_rates[len / 2]) / dec!(2)
+        } else {
+            fee_rates[len / 2]
+        };
+        Some(Self {
+            min: fee_rates[0],
+            max: fee_rates[len - 1],
+            mean: sum / Decimal::from(len),
+            median,
+            percentiles: percentiles.iter().map(|&p| (p, Self::percentile(fee_rates, p))).collect(),
+        })
+    }
+
+    /// Percentile of an already-sorted slice: the element at index
+    /// `percentile * (len - 1) / 100`, rounded down. `percentile` is clamped to
+    /// `0..=100` so an out-of-range caller gets the nearest valid percentile instead of an
+    /// out-of-bounds index.
+    fn percentile(sorted_fee_rates: &[Decimal], percentile: u8) -> Decimal {
+        let percentile = percentile.min(100);
+        let index = usize::from(percentile) * (sorted_fee_rates.len() - 1) / 100;
+        sorted_fee_rates[index]
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub total_fee: Decimal,
+    pub fee_rate: Option<FeeRateStats>,
+}
+
+impl Summary {
+    pub fn print_text(&self) {
+        println!("{}", self.total_fee);
+        if let Some(stats) = &self.fee_rate {
+            println!(
+                "fee rate (sat/vB): min={} max={} mean={} median={}",
+                stats.min, stats.max, stats.mean, stats.median
+            );
+            for (percentile, value) in &stats.percentiles {
+                println!("fee rate p{percentile}: {value}");
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error(transparent)]
+    Reqwest(#[from] reqwest::Error),
+    #[error(transparent)]
+    UrlParse(#[from] url::ParseError),
+    /// A failure from a non-Esplora backend (e.g. Electrum) that doesn't fit the other variants.
+    #[error("{0}")]
+    Backend(String),
+    /// The requested block or transaction doesn't exist on the selected network.
+    #[error("not found: {0}")]
+    NotFound(String),
+    /// The block hash or height argument was neither, or the block hash (given or resolved
+    /// from a height) doesn't start with the selected network's required prefix.
+    #[error("invalid block hash or height: {0}")]
+    InvalidHash(String),
+}
+
+/// Whether `error` is worth retrying: connection/timeout issues and 5xx responses are
+/// transient, while 4xx responses and JSON-deserialization failures are permanent.
+///
+/// The 5xx case only works because [`get_block_transaction_ids`]/[`get_transaction`] run
+/// their responses through [`ensure_found`], which calls `error_for_status()` and so turns a
+/// 5xx response into a `reqwest::Error` carrying that status; without it, a 5xx body would
+/// only surface here as a status-less JSON-decode error and never retry.
+fn is_retryable(error: &AppError) -> bool {
+    let AppError::Reqwest(error) = error else { return false };
+    if error.is_timeout() || error.is_connect() {
+        return true;
+    }
+    error.status().is_some_and(|status| status.is_server_error())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_retryable_false_for_non_reqwest_errors() {
+        assert!(!is_retryable(&AppError::Backend("boom".to_string())));
+        assert!(!is_retryable(&AppError::NotFound("boom".to_string())));
+        assert!(!is_retryable(&AppError::InvalidHash("boom".to_string())));
+    }
+
+    #[tokio::test]
+    async fn is_retryable_true_for_connection_refused() {
+        // Bind then immediately drop a listener to get a port nothing is listening on.
+        let addr = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+        let error = reqwest::Client::new()
+            .get(format!("http://{addr}"))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(is_retryable(&AppError::Reqwest(error)));
+    }
+
+    #[tokio::test]
+    async fn is_retryable_true_for_5xx_after_error_for_status() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            use std::io::{Read, Write};
+            if let Ok((mut stream, _)) = listener.accept() {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf);
+                let _ = stream.write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n");
+            }
+        });
+        let response = reqwest::Client::new().get(format!("http://{addr}")).send().await.unwrap();
+        let error = ensure_found(response).await.unwrap_err();
+        assert!(is_retryable(&error));
+    }
+
+    #[test]
+    fn fee_rate_stats_min_max_mean_median() {
+        let mut rates = vec![dec!(10), dec!(20), dec!(30), dec!(40)];
+        let stats = FeeRateStats::new(&mut rates, &[]).unwrap();
+        assert_eq!(stats.min, dec!(10));
+        assert_eq!(stats.max, dec!(40));
+        assert_eq!(stats.mean, dec!(25));
+        assert_eq!(stats.median, dec!(25)); // average of the two middle values
+
+        let mut odd_rates = vec![dec!(3), dec!(1), dec!(2)];
+        let odd_stats = FeeRateStats::new(&mut odd_rates, &[]).unwrap();
+        assert_eq!(odd_stats.median, dec!(2));
+    }
+
+    #[test]
+    fn fee_rate_stats_empty_is_none() {
+        assert!(FeeRateStats::new(&mut [], &[]).is_none());
+    }
+
+    #[test]
+    fn percentile_out_of_range_clamps_instead_of_panicking() {
+        let mut rates = vec![dec!(1), dec!(2), dec!(3), dec!(4)];
+        let stats = FeeRateStats::new(&mut rates, &[150]).unwrap();
+        assert_eq!(stats.percentiles, vec![(150, dec!(4))]); // clamped to p100 == max
+    }
+
+    struct StubBackend {
+        hash: &'static str,
+    }
+
+    #[async_trait::async_trait]
+    impl FeeBackend for StubBackend {
+        async fn get_block_txids(&self, _block_hash: &str) -> Result<Vec<String>, AppError> {
+            unimplemented!("not needed for resolve_block_hash tests")
+        }
+
+        async fn get_transaction(&self, _txid: &str) -> Result<Transaction, AppError> {
+            unimplemented!("not needed for resolve_block_hash tests")
+        }
+
+        async fn block_hash_at_height(&self, _height: u64) -> Result<String, AppError> {
+            Ok(self.hash.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_block_hash_passes_hex_input_through() {
+        let backend = StubBackend { hash: "" };
+        let hash = resolve_block_hash("00000000abcdef", Network::Mainnet, &backend, 0, 1).await.unwrap();
+        assert_eq!(hash, "00000000abcdef");
+    }
+
+    #[tokio::test]
+    async fn resolve_block_hash_resolves_height_via_backend() {
+        let backend = StubBackend { hash: "00000000deadbeef" };
+        let hash = resolve_block_hash("800000", Network::Mainnet, &backend, 0, 1).await.unwrap();
+        assert_eq!(hash, "00000000deadbeef");
+    }
+
+    #[tokio::test]
+    async fn resolve_block_hash_rejects_wrong_network_prefix() {
+        let backend = StubBackend { hash: "deadbeef" };
+        let error = resolve_block_hash("800000", Network::Mainnet, &backend, 0, 1).await.unwrap_err();
+        assert!(matches!(error, AppError::InvalidHash(hash) if hash == "deadbeef"));
+    }
+}
+
+/// Run `operation` until it succeeds, a permanent error is returned, or `max_retries` is
+/// exhausted, sleeping for an exponentially increasing delay (seeded by `base_delay_ms`)
+/// between attempts.
+pub async fn with_retry<F, Fut, T>(max_retries: u32, base_delay_ms: u64, mut operation: F) -> Result<T, AppError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, AppError>>,
+{
+    let mut backoff = ExponentialBackoff {
+        current_interval: Duration::from_millis(base_delay_ms),
+        initial_interval: Duration::from_millis(base_delay_ms),
+        max_interval: Duration::from_secs(30),
+        max_elapsed_time: None,
+        ..ExponentialBackoff::default()
+    };
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(error) if attempt < max_retries && is_retryable(&error) => {
+                attempt += 1;
+                let delay = backoff::backoff::Backoff::next_backoff(&mut backoff)
+                    .unwrap_or_else(|| Duration::from_millis(base_delay_ms));
+                warn!("Attempt {attempt}/{max_retries} failed, retrying in {delay:?}: {error}");
+                tokio::time::sleep(delay).await;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Resolve `input` to a block hash valid for `network`: passed through as-is if it's
+/// already a hex hash, otherwise parsed as an integer height and resolved via the backend.
+pub async fn resolve_block_hash(
+    input: &str,
+    network: Network,
+    backend: &dyn FeeBackend,
+    max_retries: u32,
+    base_delay_ms: u64,
+) -> Result<String, AppError> {
+    let hash = match input.parse::<u64>() {
+        Ok(height) => with_retry(max_retries, base_delay_ms, || backend.block_hash_at_height(height)).await?,
+        Err(_) if input.chars().all(|c| c.is_ascii_hexdigit()) => input.to_string(),
+        Err(_) => return Err(AppError::InvalidHash(input.to_string())),
+    };
+    if hash.starts_with(network.hash_prefix()) {
+        Ok(hash)
+    } else {
+        Err(AppError::InvalidHash(hash))
+    }
+}
+
+/// Treat a 404 response as [`AppError::NotFound`] instead of letting `.json()` fail on an
+/// error-page body.
+async fn ensure_found(response: reqwest::Response) -> Result<reqwest::Response, AppError> {
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(AppError::NotFound(response.url().to_string()));
+    }
+    Ok(response.error_for_status()?)
+}
+
+pub async fn get_block_transaction_ids(block_hash: &str, client: &Client, url: &Url) -> Result<Vec<String>, AppError> {
+    let url = url.join("block/")?.join(block_hash)?.join("txids")?;
+    let response = ensure_found(client.get(url).send().await?).await?;
+    Ok(response.json().await?)
+}
+
+pub async fn get_transaction(txid: &str, client: &Client, url: &Url) -> Result<Transaction, AppError> {
+    let url = url.join("tx/")?.join(txid)?;
+    let response = ensure_found(client.get(url).send().await?).await?;
+    Ok(response.json().await?)
+}
+
+/// Sum of the transaction fees of a block, fetched from an Esplora-compatible API.
+///
+/// This is the simplest entry point into the crate; for retries, concurrency limits, fee-rate
+/// statistics, or an Electrum backend, build on [`backend::FeeBackend`] directly instead.
+pub async fn block_fee_total(block_hash: &str, client: &Client, url: &Url) -> Result<Decimal, AppError> {
+    let block_hash = format!("{block_hash}/");
+    let txids = get_block_transaction_ids(&block_hash, client, url).await?;
+    let mut total = dec!(0);
+    for txid in txids {
+        total += get_transaction(&txid, client, url).await?.fee;
+    }
+    Ok(total)
+}
@@ -1,123 +1,188 @@
-//! Compute the sum of the transactions fees of a bitcoin block in mainnet using esplora API
+//! CLI wrapper around the `block_fees` library: parse arguments, build a backend, print a summary.
 //!
 //! Example:
 //! ```
 //! $ cargo run --release -- 000000000000000001a4f543e574f6e9d6e6e7c4ea2b84a5c1d5193a0a295995
 //! 11145972
 //! ```
-//! 
+//!
 #![warn(clippy::pedantic)]
 #[macro_use] extern crate log;
 
-use std::{error::Error,fmt::Display};
+use std::sync::Arc;
+
+use clap::{Parser, ValueEnum};
 use reqwest::{Client, Url};
-use serde::{Serialize, Deserialize};
-use rust_decimal::prelude::*;
 use rust_decimal_macros::dec;
+use tokio::sync::Semaphore;
+
+use block_fees::backend::{ElectrumBackend, EsploraBackend, FeeBackend};
+use block_fees::{resolve_block_hash, with_retry, FeeRateStats, Network as LibNetwork, Summary};
+
+/// Which Bitcoin network to query, following the same split used by `bitcoin::Network`.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Network {
+    Mainnet,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+impl From<Network> for LibNetwork {
+    fn from(network: Network) -> Self {
+        match network {
+            Network::Mainnet => LibNetwork::Mainnet,
+            Network::Testnet => LibNetwork::Testnet,
+            Network::Signet => LibNetwork::Signet,
+            Network::Regtest => LibNetwork::Regtest,
+        }
+    }
+}
+
+/// Compute the sum of the transaction fees of a bitcoin block using the Esplora API or an
+/// Electrum server.
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Args {
+    /// Block hash, or integer block height, to compute fees for.
+    block_hash: String,
+
+    /// Which network the block hash belongs to.
+    #[arg(long, value_enum, default_value_t = Network::Mainnet)]
+    network: Network,
+
+    /// Override the Esplora base URL (e.g. for a self-hosted instance).
+    #[arg(long)]
+    esplora_url: Option<String>,
+
+    /// Maximum number of retries for a transiently-failing request.
+    #[arg(long, default_value_t = 5)]
+    max_retries: u32,
 
-// Documentation: https://github.com/Blockstream/esplora/blob/master/API.md
-const ESPLORA_API: &str = "https://blockstream.info/api/";
-const MAGIC_NUMBER: &str = "00000000";
+    /// Base delay, in milliseconds, for the exponential backoff between retries.
+    #[arg(long, default_value_t = 200)]
+    base_delay_ms: u64,
+
+    /// Output format for the fee summary.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Additional fee-rate percentiles (0-100) to report, e.g. `--percentiles 10,90`.
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(0..=100))]
+    percentiles: Vec<u8>,
+
+    /// Which backend to fetch block and transaction data from.
+    #[arg(long, value_enum, default_value_t = Backend::Esplora)]
+    backend: Backend,
+
+    /// Electrum server to connect to when `--backend electrum` is selected.
+    #[arg(long)]
+    electrum_url: Option<String>,
+
+    /// Maximum number of in-flight transaction fetches. Must be at least 1.
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(usize).range(1..))]
+    concurrency: usize,
+}
+
+/// How the fee summary should be printed.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Which backend to fetch block and transaction data from.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Backend {
+    Esplora,
+    Electrum,
+}
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
-    let block_hash = if let Some(s) =  std::env::args().nth(1){ s + "/" } else {
-        error!("Please provide a Bitcoin mainnet block hash.");
-        std::process::exit(1)
+    let args = Args::parse();
+    let network = LibNetwork::from(args.network);
+    let max_retries = args.max_retries;
+    let base_delay_ms = args.base_delay_ms;
+
+    let backend: Arc<dyn FeeBackend> = match args.backend {
+        Backend::Esplora => {
+            // FIXME: could panic
+            let client = Client::new();
+            let base_url = args.esplora_url.as_deref().unwrap_or_else(|| network.default_esplora_url());
+            let url = Url::parse(base_url).expect("URL parsing failed.");
+            Arc::new(EsploraBackend::new(client, url))
+        }
+        Backend::Electrum => {
+            let electrum_url = args.electrum_url.unwrap_or_else(|| {
+                error!("--electrum-url is required when --backend electrum is selected.");
+                std::process::exit(1)
+            });
+            Arc::new(ElectrumBackend::connect(&electrum_url).unwrap_or_else(|error| {
+                error!("Error connecting to Electrum server: {error}");
+                std::process::exit(1)
+            }))
+        }
     };
-    if !block_hash.starts_with(MAGIC_NUMBER) {
-        error!("Invalid hash. Please provide a Bitcoin mainnet block hash.");
-        std::process::exit(1)
-    }
-    // FIXME: could panic
-    let client = Client::new();
-    let url = Url::parse(ESPLORA_API).expect("URL parsing failed.");
+
+    let resolved_hash = resolve_block_hash(&args.block_hash, network, backend.as_ref(), max_retries, base_delay_ms)
+        .await.unwrap_or_else(|error|{
+            error!("Error resolving block hash: {:?}", error);
+            std::process::exit(1)
+    });
+    let block_hash = resolved_hash + "/";
 
     // TODO: more informative error messages
-    let block_transaction_ids = get_block_transaction_ids(&block_hash, &client, &url)
+    let block_transaction_ids = with_retry(max_retries, base_delay_ms, || {
+            backend.get_block_txids(&block_hash)
+        })
         .await.unwrap_or_else(|error|{
             error!("Error fetching transactions from block ID: {:?}", error);
             std::process::exit(1)
     });
 
-    //TODO: improve asynchronicity
+    // Bounded by `--concurrency`: each spawned fetch holds a permit for its duration, so at
+    // most `args.concurrency` requests are ever in flight regardless of block size.
+    let semaphore = Arc::new(Semaphore::new(args.concurrency));
     let mut handles = Vec::with_capacity(block_transaction_ids.len());
     for (i, txid) in block_transaction_ids.into_iter().enumerate(){
-        let client = client.clone();
-        let url = url.clone();
+        let backend = backend.clone();
+        let semaphore = semaphore.clone();
         info!("Spawning transacion {i}");
         let handle = tokio::spawn(async move{
-            let fee = get_fee_from_txid(txid.as_ref(), &client, &url).await.unwrap_or_else(|error|{
-                error!("Error fetching fee from one or more transactions: {:?}", error);
-                std::process::exit(1)
-            });
+            let _permit = semaphore.acquire_owned().await.expect("Semaphore closed unexpectedly.");
+            let transaction = with_retry(max_retries, base_delay_ms, || {
+                    backend.get_transaction(txid.as_ref())
+                })
+                .await.unwrap_or_else(|error|{
+                    error!("Error fetching fee from one or more transactions: {:?}", error);
+                    std::process::exit(1)
+                });
             info!("Finished transaction {i}");
-            fee
+            transaction
         });
-        handles.push(handle); 
-    }    
-
-    let mut sum = dec!(0);
-    for handle in handles{
-        let fee = handle.await.expect("Failed to join a thread.");
-        sum += fee;
+        handles.push(handle);
     }
-    println!("{sum}");
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Transaction{
-    fee: Decimal
-}
-
-#[derive(Debug)]
-enum AppError {
-    ReqwestError(reqwest::Error),
-    UrlParseError(url::ParseError)
-}
-impl Display for AppError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            AppError::ReqwestError(error) => write!(f, "{error}"),
-            AppError::UrlParseError(error) => write!(f, "{error}")
 
+    let mut sum = dec!(0);
+    let mut fee_rates = Vec::with_capacity(handles.len());
+    for (i, handle) in handles.into_iter().enumerate(){
+        let transaction = handle.await.expect("Failed to join a thread.");
+        sum += transaction.fee;
+        // The coinbase transaction (always first) has no real fee rate to report.
+        if i != 0 {
+            fee_rates.push(transaction.fee_rate());
         }
     }
-}
-impl Error for AppError{}
-impl From<reqwest::Error> for AppError{
-    fn from(err: reqwest::Error) -> Self {
-        AppError::ReqwestError(err)
-    }
-}
-impl From<url::ParseError> for AppError{
-    fn from(err: url::ParseError) -> Self {
-        AppError::UrlParseError(err)
-    }
-}
-    
-
-async fn get_block_transaction_ids(block_hash: &str, client: &Client, url: &Url) -> Result<Vec<String>, AppError>{
-    let url = url.join("block/")?.join(block_hash)?.join("txids")?;
-    let response = client.get(
-            url
-        )
-        .send()
-        .await?
-        .json()
-        .await?;
-    Ok(response)
-}
 
-async fn get_fee_from_txid(txid: &str, client: &Client, url: &Url) -> Result<Decimal, AppError>{
-    let response: Transaction = client.get(
-        url.join("tx/")?.join(txid)?
-        )
-        .send()
-        .await?
-        .json()
-        .await?;
-    Ok(response.fee)
+    let summary = Summary {
+        total_fee: sum,
+        fee_rate: FeeRateStats::new(&mut fee_rates, &args.percentiles),
+    };
+    match args.format {
+        OutputFormat::Text => summary.print_text(),
+        OutputFormat::Json => println!("{}", serde_json::to_string(&summary).expect("Failed to serialize summary.")),
+    }
 }